@@ -17,28 +17,58 @@ use turbo_tasks_hash::{hash_xxh3_hash64, DeterministicHash, DeterministicHasher}
 
 type Bytes = Vec<u8>;
 
+/// The largest chunk that [RopeElem::Inline] stores without spilling to a
+/// heap allocation. 23 bytes keeps `Inline`'s own payload (buffer + length)
+/// at 24 bytes, the elastic-array inline-then-spill convention; `RopeElem`
+/// as a whole still ends up 32 bytes once the `Shared(Arc<Bytes>)` variant
+/// and the enum's discriminant are factored in, so this isn't about keeping
+/// `RopeElem` word-sized -- it's about avoiding a heap allocation for every
+/// small write.
+const INLINE_CAPACITY: usize = 23;
+
+/// A balanced binary rope tree, after the representation described in
+/// Boehm, Atkinson & Plass's "Ropes: an Alternative to Strings". A `Node`
+/// caches its `weight` (the byte length of its left subtree, used to
+/// descend in O(log n)), its total `length`, and its `depth`/`leaves` count
+/// (used to detect when the tree needs rebalancing), so all of these stay
+/// O(1) to read after a `concat`.
 #[turbo_tasks::value(shared, serialization = "none", eq = "manual")]
 #[derive(Clone, Debug)]
 pub enum Rope {
-    Flat(RopeElem),
-    Concat { length: usize, data: Vec<RopeElem> },
+    Leaf(RopeElem),
+    Node {
+        weight: usize,
+        length: usize,
+        depth: usize,
+        leaves: usize,
+        left: Box<Rope>,
+        right: Box<Rope>,
+    },
 }
 
+/// A single chunk of a [Rope]. Small chunks (up to [INLINE_CAPACITY] bytes)
+/// are stored inline to avoid a heap allocation per `push_bytes` call; larger
+/// or explicitly shared chunks are reference-counted so producers can hand
+/// a rope bytes without copying them.
 #[turbo_tasks::value(shared)]
 #[derive(Clone)]
-pub struct RopeElem(#[turbo_tasks(debug_ignore)] Arc<Bytes>);
+pub enum RopeElem {
+    Inline { buf: [u8; INLINE_CAPACITY], len: u8 },
+    Shared(#[turbo_tasks(debug_ignore)] Arc<Bytes>),
+}
 
-use Rope::{Concat, Flat};
+use Rope::{Leaf, Node};
 
 impl Rope {
     pub fn new(bytes: Bytes) -> Self {
-        Flat(RopeElem::new(bytes))
+        Leaf(RopeElem::new(bytes))
     }
 
     pub fn flatten(&self) -> Cow<'_, Bytes> {
         match self {
-            Rope::Flat(data) => Cow::Borrowed(data),
-            Rope::Concat { .. } => {
+            Leaf(RopeElem::Shared(bytes)) => Cow::Borrowed(bytes),
+            Leaf(el @ RopeElem::Inline { .. }) => Cow::Owned(el.as_slice().to_vec()),
+            Node { .. } => {
                 let mut buf = Vec::with_capacity(self.len());
                 self.flatten_internal(&mut buf);
                 Cow::Owned(buf)
@@ -47,80 +77,79 @@ impl Rope {
     }
 
     pub fn push_bytes(&mut self, bytes: Bytes) {
-        let last_mut = match self {
-            Flat(data) => Some(data),
-            Concat { data, .. } => data.last_mut(),
+        if bytes.is_empty() {
+            return;
         }
-        .and_then(|l| Arc::get_mut(l));
-
-        if let Some(last) = last_mut {
-            let l = bytes.len();
-            last.extend(bytes);
-
-            if let Concat { length, .. } = self {
-                *length += l;
-            }
-        } else {
-            self.push_shared_bytes(Arc::new(bytes));
+        if !self.try_extend_last(&bytes) {
+            // The rightmost leaf couldn't be extended in place (e.g. it's a
+            // shared `Arc` with other owners), so this write needs a new
+            // leaf. Go through `RopeElem::new` rather than
+            // `push_shared_bytes` so a small `bytes` still inlines instead
+            // of forcing a fresh heap `Arc` per write.
+            self.concat(&Leaf(RopeElem::new(bytes)));
         }
     }
 
-    pub fn push_shared_bytes(&mut self, bytes: Arc<Bytes>) {
+    /// Recursively finds this subtree's rightmost leaf and tries to extend
+    /// it in place (see [RopeElem::try_extend]), bumping `length` on every
+    /// `Node` along the way if it succeeds.
+    fn try_extend_last(&mut self, bytes: &Bytes) -> bool {
         match self {
-            Flat(data) => {
-                let length = data.len() + bytes.len();
-                *self = Concat {
-                    length,
-                    data: vec![data.clone(), RopeElem(bytes)],
-                };
-            }
-            Concat { length, data } => {
-                *length += bytes.len();
-                data.push(RopeElem(bytes));
+            Leaf(el) => el.try_extend(bytes),
+            Node { length, right, .. } => {
+                if right.try_extend_last(bytes) {
+                    *length += bytes.len();
+                    true
+                } else {
+                    false
+                }
             }
         }
     }
 
+    pub fn push_shared_bytes(&mut self, bytes: Arc<Bytes>) {
+        self.concat(&Leaf(RopeElem::Shared(bytes)));
+    }
+
+    /// Appends `other` by creating a new root node over `self` and a clone of
+    /// `other`. Cloning `other` copies each of its tree nodes, so this is
+    /// O(1) only when `other` is a single leaf. If the resulting tree's depth
+    /// exceeds the [fib_depth_limit] for its leaf count, it is immediately
+    /// rebuilt into a balanced tree.
     pub fn concat(&mut self, other: &Rope) {
-        match self {
-            Flat(left) => match other {
-                Flat(right) => {
-                    let length = left.len() + other.len();
-                    *self = Concat {
-                        length,
-                        data: vec![left.clone(), right.clone()],
-                    };
-                }
-                Concat {
-                    length: rlen,
-                    data: right,
-                } => {
-                    let length = left.len() + rlen;
-                    let mut data = Vec::with_capacity(right.len() + 1);
-                    data.push(left.clone());
-                    data.extend(right.clone());
-                    *self = Concat { length, data };
-                }
-            },
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = other.clone();
+            return;
+        }
 
-            Concat { length, data: left } => {
-                *length += other.len();
-                match other {
-                    Flat(right) => {
-                        left.push(right.clone());
-                    }
-                    Concat { data: right, .. } => {
-                        left.extend(right.clone());
-                    }
-                }
-            }
+        let left = Box::new(std::mem::replace(self, Rope::default()));
+        let right = Box::new(other.clone());
+        let weight = left.len();
+        let length = weight + right.len();
+        let depth = 1 + left.depth().max(right.depth());
+        let leaves = left.leaf_count() + right.leaf_count();
+
+        *self = Node {
+            weight,
+            length,
+            depth,
+            leaves,
+            left,
+            right,
+        };
+
+        if depth > fib_depth_limit(leaves) {
+            self.rebalance();
         }
     }
 
     pub fn len(&self) -> usize {
         match self {
-            Flat(data) => data.len(),
-            Concat { length, .. } => *length,
+            Leaf(data) => data.len(),
+            Node { length, .. } => *length,
         }
     }
 
@@ -128,6 +157,70 @@ impl Rope {
         self.len() == 0
     }
 
+    fn depth(&self) -> usize {
+        match self {
+            Leaf(_) => 0,
+            Node { depth, .. } => *depth,
+        }
+    }
+
+    fn leaf_count(&self) -> usize {
+        match self {
+            Leaf(_) => 1,
+            Node { leaves, .. } => *leaves,
+        }
+    }
+
+    /// Rebuilds this subtree into a balanced tree: collects all of its
+    /// leaves in order, then merges them pairwise, bottom-up, until a
+    /// single tree remains.
+    fn rebalance(&mut self) {
+        let mut leaves = Vec::with_capacity(self.leaf_count());
+        self.collect_leaves(&mut leaves);
+        *self = Rope::from_leaves(leaves);
+    }
+
+    fn collect_leaves(&self, out: &mut Vec<RopeElem>) {
+        match self {
+            Leaf(el) => out.push(el.clone()),
+            Node { left, right, .. } => {
+                left.collect_leaves(out);
+                right.collect_leaves(out);
+            }
+        }
+    }
+
+    fn from_leaves(leaves: Vec<RopeElem>) -> Rope {
+        let mut level: Vec<Rope> = leaves.into_iter().map(Leaf).collect();
+        while level.len() > 1 {
+            let mut next = Vec::with_capacity((level.len() + 1) / 2);
+            let mut iter = level.into_iter();
+            while let Some(left) = iter.next() {
+                next.push(match iter.next() {
+                    Some(right) => Rope::new_node(left, right),
+                    None => left,
+                });
+            }
+            level = next;
+        }
+        level.into_iter().next().unwrap_or_default()
+    }
+
+    fn new_node(left: Rope, right: Rope) -> Rope {
+        let weight = left.len();
+        let length = weight + right.len();
+        let depth = 1 + left.depth().max(right.depth());
+        let leaves = left.leaf_count() + right.leaf_count();
+        Node {
+            weight,
+            length,
+            depth,
+            leaves,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
     pub fn slice(&'_ self, start: usize, end: usize) -> RopeReader<'_> {
         RopeReader::new_slice(self, start, end)
     }
@@ -136,6 +229,14 @@ impl Rope {
         RopeReader::new_full(self)
     }
 
+    /// Streams this rope's bytes through `transform` in fixed-size blocks,
+    /// without ever materializing [flatten](Self::flatten)'s whole output.
+    /// Useful for applying a streaming codec (gzip/brotli compression,
+    /// encryption, ...) to an asset body served directly from a rope.
+    pub fn transform<T: RopeTransform>(&'_ self, transform: T) -> RopeTransformReader<'_, T> {
+        RopeTransformReader::new(self, transform)
+    }
+
     pub fn to_string(&self) -> Result<String> {
         let mut read = self.read();
         let mut string = String::new();
@@ -146,16 +247,229 @@ impl Rope {
 
     fn flatten_internal(&self, buf: &mut Bytes) {
         match self {
-            Flat(data) => buf.extend(&***data),
-            Concat { data, .. } => {
-                for v in data {
-                    buf.extend(&***v);
+            Leaf(data) => buf.extend(data.as_slice()),
+            Node { left, right, .. } => {
+                left.flatten_internal(buf);
+                right.flatten_internal(buf);
+            }
+        }
+    }
+
+    /// Computes the edits needed to turn `self` into `other`, diffing line by
+    /// line with the Myers O(ND) algorithm.
+    ///
+    /// This is meant for incremental HMR patches and cache invalidation,
+    /// where re-emitting the whole rope on every change is wasteful. A
+    /// newline-terminated line that is exactly one whole leaf reuses that
+    /// leaf's `RopeElem` (and so its `Arc`); every other line, including one
+    /// that covers only part of a leaf, is copied into a new chunk.
+    pub fn diff(&self, other: &Rope) -> RopeDiff {
+        let left = self.diff_lines();
+        let right = other.diff_lines();
+        let ops = myers_trace(&left, &right);
+        RopeDiff(collapse_diff_ops(ops, left, right))
+    }
+
+    /// Splits the rope into line units, where a unit is the bytes up to and
+    /// including a trailing `\n`, or a final partial line with none. A
+    /// `\n`-terminated line that exactly matches a single leaf reuses that
+    /// leaf; a line that straddles a leaf boundary, only covers part of one,
+    /// or is the final partial line, is copied into a new owned chunk.
+    fn diff_lines(&self) -> Vec<Rope> {
+        let mut elems = Vec::with_capacity(self.leaf_count());
+        self.collect_leaves(&mut elems);
+
+        let mut lines = Vec::new();
+        let mut pending: Option<Bytes> = None;
+
+        for el in &elems {
+            let bytes: &[u8] = el.as_slice();
+            let mut start = 0;
+            while let Some(nl) = bytes[start..].iter().position(|&b| b == b'\n') {
+                let end = start + nl + 1;
+                match pending.take() {
+                    Some(mut carry) => {
+                        carry.extend_from_slice(&bytes[start..end]);
+                        lines.push(Rope::new(carry));
+                    }
+                    None if start == 0 && end == bytes.len() => {
+                        lines.push(Leaf(el.clone()));
+                    }
+                    None => lines.push(Rope::new(bytes[start..end].to_vec())),
                 }
+                start = end;
+            }
+            if start < bytes.len() {
+                let rest = &bytes[start..];
+                pending = Some(match pending.take() {
+                    Some(mut carry) => {
+                        carry.extend_from_slice(rest);
+                        carry
+                    }
+                    None => rest.to_vec(),
+                });
             }
         }
+        if let Some(carry) = pending {
+            lines.push(Rope::new(carry));
+        }
+        lines
     }
 }
 
+/// A single operation in a [RopeDiff]: a run of lines common to both ropes,
+/// or a run inserted/deleted to turn one rope into the other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RopeDiffSpan {
+    Equal(Rope),
+    Insert(Rope),
+    Delete(Rope),
+}
+
+/// The ordered list of [RopeDiffSpan]s produced by [Rope::diff].
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct RopeDiff(pub Vec<RopeDiffSpan>);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DiffOp {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// Returns the smallest `n` such that the `n`-th Fibonacci number (with
+/// `F(1) = F(2) = 1`) is at least `n_leaves`. A rope subtree is considered
+/// balanced as long as its depth doesn't exceed this limit for its leaf
+/// count. This has the Fibonacci form of the original ropes paper's
+/// invariant, phrased in terms of leaves instead of subtree length, but is
+/// looser than the paper's `F(depth + 2) <= length`: with two or more
+/// leaves, a tree may grow two or three levels deeper before it is
+/// rebalanced.
+fn fib_depth_limit(n_leaves: usize) -> usize {
+    if n_leaves <= 1 {
+        return 1;
+    }
+    let (mut a, mut b) = (1usize, 1usize);
+    let mut n = 2;
+    while b < n_leaves {
+        let next = a + b;
+        a = b;
+        b = next;
+        n += 1;
+    }
+    n
+}
+
+/// Finds the shortest edit script between two unit sequences using Myers'
+/// O(ND) algorithm: for each edit distance `d`, the furthest-reaching x on
+/// every diagonal `k = x - y` is tracked in `v`, snapshotting `v` at each
+/// step so the path can be recovered by backtracking from the end.
+fn myers_trace(left: &[Rope], right: &[Rope]) -> Vec<DiffOp> {
+    let n = left.len() as isize;
+    let m = right.len() as isize;
+    let max_d = n + m;
+    if max_d == 0 {
+        return Vec::new();
+    }
+
+    let offset = max_d as usize;
+    let mut v = vec![0isize; 2 * max_d as usize + 2];
+    // Bootstraps d == 0, k == 0 as though the path arrived at x = 0 via k = 1.
+    v[offset + 1] = 0;
+
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut last_d = max_d;
+
+    'search: for d in 0..=max_d {
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset as isize) as usize;
+            let down = k == -d || (k != d && v[idx - 1] < v[idx + 1]);
+            let mut x = if down { v[idx + 1] } else { v[idx - 1] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && left[x as usize] == right[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                last_d = d;
+                trace.push(v.clone());
+                break 'search;
+            }
+        }
+        trace.push(v.clone());
+    }
+
+    let mut ops = Vec::with_capacity((n + m) as usize);
+    let mut x = n;
+    let mut y = m;
+    for d in (0..=last_d).rev() {
+        let v = &trace[d as usize];
+        let k = x - y;
+        let idx = (k + offset as isize) as usize;
+        let down = k == -d || (k != d && v[idx - 1] < v[idx + 1]);
+        let prev_k = if down { k + 1 } else { k - 1 };
+        let prev_x = v[(prev_k + offset as isize) as usize];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            ops.push(DiffOp::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            ops.push(if x == prev_x {
+                DiffOp::Insert
+            } else {
+                DiffOp::Delete
+            });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    ops
+}
+
+/// Walks an edit script, consuming units from `left`/`right` as it goes, and
+/// merges consecutive same-kind units into a single [RopeDiffSpan] using
+/// [Rope::concat] so runs of unchanged lines stay as few spans as possible.
+fn collapse_diff_ops(ops: Vec<DiffOp>, left: Vec<Rope>, right: Vec<Rope>) -> Vec<RopeDiffSpan> {
+    let mut left = left.into_iter();
+    let mut right = right.into_iter();
+    let mut spans: Vec<RopeDiffSpan> = Vec::new();
+
+    for op in ops {
+        let unit = match op {
+            DiffOp::Equal => {
+                right.next();
+                left.next().unwrap()
+            }
+            DiffOp::Delete => left.next().unwrap(),
+            DiffOp::Insert => right.next().unwrap(),
+        };
+
+        let extended = match (spans.last_mut(), op) {
+            (Some(RopeDiffSpan::Equal(r)), DiffOp::Equal)
+            | (Some(RopeDiffSpan::Insert(r)), DiffOp::Insert)
+            | (Some(RopeDiffSpan::Delete(r)), DiffOp::Delete) => {
+                r.concat(&unit);
+                true
+            }
+            _ => false,
+        };
+
+        if !extended {
+            spans.push(match op {
+                DiffOp::Equal => RopeDiffSpan::Equal(unit),
+                DiffOp::Insert => RopeDiffSpan::Insert(unit),
+                DiffOp::Delete => RopeDiffSpan::Delete(unit),
+            });
+        }
+    }
+    spans
+}
+
 impl Default for Rope {
     fn default() -> Self {
         vec![].into()
@@ -208,11 +522,10 @@ impl DeterministicHash for Rope {
     /// structure.
     fn deterministic_hash<H: DeterministicHasher>(&self, state: &mut H) {
         match self {
-            Flat(f) => state.write_bytes(f.as_slice()),
-            Concat { data, .. } => {
-                for v in data {
-                    v.deterministic_hash(state);
-                }
+            Leaf(f) => state.write_bytes(f.as_slice()),
+            Node { left, right, .. } => {
+                left.deterministic_hash(state);
+                right.deterministic_hash(state);
             }
         }
     }
@@ -223,11 +536,10 @@ impl Hash for Rope {
     /// structure.
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
-            Flat(f) => state.write(f.as_slice()),
-            Concat { data, .. } => {
-                for v in data {
-                    v.hash(state);
-                }
+            Leaf(f) => state.write(f.as_slice()),
+            Node { left, right, .. } => {
+                left.hash(state);
+                right.hash(state);
             }
         }
     }
@@ -263,78 +575,172 @@ impl<'de> Deserialize<'de> for Rope {
     }
 }
 
-impl ops::Deref for RopeElem {
-    type Target = Arc<Bytes>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl ops::DerefMut for RopeElem {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
-}
-
 impl Debug for RopeElem {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
-        let ty = if Arc::strong_count(self) > 1 {
-            "Shared"
-        } else {
-            "Owned"
+        let ty = match self {
+            Self::Inline { .. } => "Inline",
+            Self::Shared(arc) if Arc::strong_count(arc) > 1 => "Shared",
+            Self::Shared(_) => "Owned",
         };
-        let data = std::str::from_utf8(self).unwrap_or("[u8 bytes]");
+        let data = std::str::from_utf8(self.as_slice()).unwrap_or("[u8 bytes]");
         f.debug_tuple(ty).field(&data).finish()
     }
 }
 
 impl RopeElem {
     fn new(bytes: Bytes) -> Self {
-        Self(Arc::new(bytes))
+        if bytes.len() <= INLINE_CAPACITY {
+            let mut buf = [0u8; INLINE_CAPACITY];
+            buf[..bytes.len()].copy_from_slice(&bytes);
+            Self::Inline {
+                buf,
+                len: bytes.len() as u8,
+            }
+        } else {
+            Self::Shared(Arc::new(bytes))
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            Self::Inline { buf, len } => &buf[..*len as usize],
+            Self::Shared(bytes) => bytes,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    /// Extends this chunk in place with `bytes`, if possible: an `Inline`
+    /// chunk grows in place until it would exceed [INLINE_CAPACITY], at which
+    /// point it spills into a single heap allocation holding both the old
+    /// and new bytes; a uniquely-owned `Shared` chunk is extended directly.
+    /// Returns `false` (leaving `self` untouched) when `bytes` can't be
+    /// merged in, meaning the caller should push a new element instead.
+    fn try_extend(&mut self, bytes: &Bytes) -> bool {
+        match self {
+            Self::Inline { buf, len } => {
+                let cur = *len as usize;
+                if cur + bytes.len() <= INLINE_CAPACITY {
+                    buf[cur..cur + bytes.len()].copy_from_slice(bytes);
+                    *len = (cur + bytes.len()) as u8;
+                } else {
+                    let mut merged = buf[..cur].to_vec();
+                    merged.extend_from_slice(bytes);
+                    *self = Self::Shared(Arc::new(merged));
+                }
+                true
+            }
+            Self::Shared(arc) => match Arc::get_mut(arc) {
+                Some(vec) => {
+                    vec.extend_from_slice(bytes);
+                    true
+                }
+                None => false,
+            },
+        }
     }
 }
 
+/// Reads a [Rope]'s bytes in order. Rather than holding a cursor into a flat
+/// byte array, it walks the rope tree with an explicit descent stack of
+/// not-yet-visited right subtrees, so it never needs to know the tree's
+/// shape up front.
 pub struct RopeReader<'a> {
-    rope: &'a Rope,
+    /// Right subtrees skipped over on the way to `leaf`, outermost first, so
+    /// popping one off and descending its left spine resumes the in-order
+    /// walk.
+    pending: Vec<&'a Rope>,
+    leaf: Option<&'a RopeElem>,
     byte_pos: usize,
-    concat_index: usize,
     max_bytes: usize,
 }
 
 impl<'a> RopeReader<'a> {
     fn new_full(rope: &'a Rope) -> Self {
-        RopeReader {
-            rope,
+        let mut reader = RopeReader {
+            pending: Vec::new(),
+            leaf: None,
             byte_pos: 0,
-            concat_index: 0,
             max_bytes: rope.len(),
-        }
+        };
+        reader.descend(rope);
+        reader
     }
 
     fn new_slice(rope: &'a Rope, start: usize, end: usize) -> Self {
-        let mut reader = RopeReader::new_full(rope);
-        reader.read_internal(start, &mut None);
-        reader.max_bytes = end - start;
+        let mut reader = RopeReader {
+            pending: Vec::new(),
+            leaf: None,
+            byte_pos: 0,
+            max_bytes: end - start,
+        };
+        reader.seek(rope, start);
         reader
     }
 
-    fn read_internal(&mut self, want: usize, buf: &mut Option<&mut ReadBuf<'_>>) -> usize {
-        let mut remaining = want;
+    /// Descends from `node` to its leftmost leaf, pushing each unvisited
+    /// right subtree onto `pending` so the walk can resume from there once
+    /// the current leaf is exhausted.
+    fn descend(&mut self, mut node: &'a Rope) {
+        loop {
+            match node {
+                Leaf(el) => {
+                    self.leaf = Some(el);
+                    return;
+                }
+                Node { left, right, .. } => {
+                    self.pending.push(right);
+                    node = left;
+                }
+            }
+        }
+    }
 
-        while remaining > 0 {
-            let el = match self.rope {
-                Flat(el) => {
-                    if self.concat_index > 0 {
-                        break;
+    /// Descends to the leaf containing byte `offset` of `node`, comparing
+    /// `offset` against each node's `weight` to go left or right in O(log
+    /// n), and leaves `pending` set up to continue the walk forward from
+    /// there.
+    fn seek(&mut self, mut node: &'a Rope, mut offset: usize) {
+        loop {
+            match node {
+                Leaf(el) => {
+                    self.leaf = Some(el);
+                    self.byte_pos = offset;
+                    return;
+                }
+                Node {
+                    weight, left, right, ..
+                } => {
+                    if offset < *weight {
+                        self.pending.push(right);
+                        node = left;
+                    } else {
+                        offset -= *weight;
+                        node = right;
                     }
-                    el
                 }
+            }
+        }
+    }
+
+    /// Moves on to the next leaf in the walk, if any.
+    fn advance_leaf(&mut self) {
+        self.byte_pos = 0;
+        match self.pending.pop() {
+            Some(node) => self.descend(node),
+            None => self.leaf = None,
+        }
+    }
+
+    fn read_internal(&mut self, want: usize, buf: &mut Option<&mut ReadBuf<'_>>) -> usize {
+        let mut remaining = want;
 
-                Concat { data, .. } => match data.get(self.concat_index) {
-                    Some(el) => el,
-                    None => break,
-                },
+        while remaining > 0 {
+            let el = match self.leaf {
+                Some(el) => el,
+                None => break,
             };
 
             let got = self.read_bytes(el, remaining, buf);
@@ -349,17 +755,17 @@ impl<'a> RopeReader<'a> {
 
     fn read_bytes(
         &mut self,
-        bytes: &Vec<u8>,
+        el: &RopeElem,
         remaining: usize,
         buf: &mut Option<&mut ReadBuf<'_>>,
     ) -> usize {
+        let bytes = el.as_slice();
         let pos = self.byte_pos;
         let amount = min(min(bytes.len() - pos, remaining), self.max_bytes);
         let end = pos + amount;
 
         if end == bytes.len() {
-            self.byte_pos = 0;
-            self.concat_index += 1;
+            self.advance_leaf();
         } else {
             self.byte_pos = end;
         }
@@ -387,4 +793,406 @@ impl<'a> AsyncRead for RopeReader<'a> {
         this.read_internal(buf.remaining(), &mut Some(buf));
         Poll::Ready(Ok(()))
     }
+}
+
+/// A streaming byte transform that can be applied to a [Rope]'s contents via
+/// [RopeTransformReader], one fixed-size block at a time.
+pub trait RopeTransform {
+    /// Transforms one input block, appending the result to `out`. `input` is
+    /// `block_size` bytes long, except possibly the very last call before
+    /// EOF.
+    fn transform_block(&mut self, input: &[u8], out: &mut Vec<u8>);
+
+    /// Called exactly once, after the last `transform_block` call, to flush
+    /// any remaining internal state (e.g. a compressor's trailer) into
+    /// `out`.
+    fn finalize(&mut self, out: &mut Vec<u8>);
+}
+
+/// Feeds a [Rope]'s bytes through a [RopeTransform] in fixed-size blocks and
+/// exposes the transformed output as a `Read`/`AsyncRead` stream, so callers
+/// never need to materialize the whole flattened rope to stream-compress or
+/// stream-encrypt it. Input is buffered across `RopeElem` boundaries so the
+/// transform always sees full blocks, except for the final (possibly short)
+/// one immediately before [RopeTransform::finalize] runs.
+pub struct RopeTransformReader<'a, T: RopeTransform> {
+    inner: RopeReader<'a>,
+    transform: T,
+    block_size: usize,
+    input_buf: Bytes,
+    output_buf: Bytes,
+    output_pos: usize,
+    finished: bool,
+}
+
+impl<'a, T: RopeTransform> RopeTransformReader<'a, T> {
+    const DEFAULT_BLOCK_SIZE: usize = 8 * 1024;
+
+    pub fn new(rope: &'a Rope, transform: T) -> Self {
+        Self::with_block_size(rope, transform, Self::DEFAULT_BLOCK_SIZE)
+    }
+
+    pub fn with_block_size(rope: &'a Rope, transform: T, block_size: usize) -> Self {
+        let block_size = block_size.max(1);
+        RopeTransformReader {
+            inner: rope.read(),
+            transform,
+            block_size,
+            input_buf: Vec::with_capacity(block_size),
+            output_buf: Vec::new(),
+            output_pos: 0,
+            finished: false,
+        }
+    }
+
+    /// Ensures `output_buf` has unread bytes, or that `finished` is set,
+    /// pulling as many full blocks from `inner` as needed and running them
+    /// (or the final partial block, then [RopeTransform::finalize]) through
+    /// the transform.
+    fn fill_output(&mut self) -> IoResult<()> {
+        while self.output_pos >= self.output_buf.len() && !self.finished {
+            self.output_buf.clear();
+            self.output_pos = 0;
+
+            while self.input_buf.len() < self.block_size {
+                let mut chunk = vec![0u8; self.block_size - self.input_buf.len()];
+                let got = self.inner.read(&mut chunk)?;
+                if got == 0 {
+                    break;
+                }
+                self.input_buf.extend_from_slice(&chunk[..got]);
+            }
+
+            if self.input_buf.len() >= self.block_size {
+                let block: Vec<u8> = self.input_buf.drain(..self.block_size).collect();
+                self.transform.transform_block(&block, &mut self.output_buf);
+            } else {
+                if !self.input_buf.is_empty() {
+                    let block = std::mem::take(&mut self.input_buf);
+                    self.transform.transform_block(&block, &mut self.output_buf);
+                }
+                self.transform.finalize(&mut self.output_buf);
+                self.finished = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T: RopeTransform> Read for RopeTransformReader<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill_output()?;
+        let avail = &self.output_buf[self.output_pos..];
+        let n = min(avail.len(), buf.len());
+        buf[..n].copy_from_slice(&avail[..n]);
+        self.output_pos += n;
+        Ok(n)
+    }
+}
+
+impl<'a, T: RopeTransform + Unpin> AsyncRead for RopeTransformReader<'a, T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Err(err) = this.fill_output() {
+            return Poll::Ready(Err(err));
+        }
+        let avail = &this.output_buf[this.output_pos..];
+        let n = min(avail.len(), buf.remaining());
+        buf.put_slice(&avail[..n]);
+        this.output_pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod transform_tests {
+    use super::*;
+
+    /// Uppercases every byte, one block at a time; `finalize` appends a
+    /// marker so tests can also confirm it only runs once, at the end.
+    struct UppercaseTransform;
+
+    impl RopeTransform for UppercaseTransform {
+        fn transform_block(&mut self, input: &[u8], out: &mut Vec<u8>) {
+            out.extend(input.iter().map(u8::to_ascii_uppercase));
+        }
+
+        fn finalize(&mut self, out: &mut Vec<u8>) {
+            out.extend_from_slice(b"!");
+        }
+    }
+
+    fn rope_from_chunks(chunks: &[&str]) -> Rope {
+        let mut rope = Rope::default();
+        for chunk in chunks {
+            rope.push_shared_bytes(Arc::new(chunk.as_bytes().to_vec()));
+        }
+        rope
+    }
+
+    fn transform_with_block_size(rope: &Rope, block_size: usize) -> Vec<u8> {
+        let mut reader = RopeTransformReader::with_block_size(rope, UppercaseTransform, block_size);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        out
+    }
+
+    fn reference(rope: &Rope) -> Vec<u8> {
+        let mut out: Vec<u8> = rope
+            .flatten()
+            .iter()
+            .map(u8::to_ascii_uppercase)
+            .collect();
+        out.extend_from_slice(b"!");
+        out
+    }
+
+    #[test]
+    fn matches_reference_with_block_size_dividing_length() {
+        let rope = rope_from_chunks(&["abc", "defgh", "ij"]);
+        assert_eq!(rope.len(), 10);
+        assert_eq!(transform_with_block_size(&rope, 2), reference(&rope));
+    }
+
+    #[test]
+    fn matches_reference_with_block_size_not_dividing_length() {
+        let rope = rope_from_chunks(&["abc", "defgh", "ij"]);
+        assert_eq!(rope.len(), 10);
+        assert_eq!(transform_with_block_size(&rope, 4), reference(&rope));
+    }
+}
+
+/// Opt-in, structure-preserving serialization for [Rope]s.
+///
+/// [Rope]'s regular `Serialize`/`Deserialize` impls above flatten every rope
+/// into a contiguous string, which discards all `Arc<Bytes>` sharing. That's
+/// fine for a single rope, but a persistent cache holding thousands of ropes
+/// that share common chunks (license headers, runtime preludes, sourcemap
+/// boilerplate) pays for that sharing on every serialization. [RopeStore]
+/// instead keys each distinct chunk by its content hash and writes it once
+/// into a side table, so identical chunks across the whole serialized graph
+/// become hash references; deserializing rebuilds the shared `Arc`s so that
+/// identical chunks point at the same allocation again.
+pub mod store {
+    use std::{collections::HashMap, sync::Arc};
+
+    use serde::{Deserialize, Serialize};
+    use turbo_tasks_hash::hash_xxh3_hash64;
+
+    use super::{Bytes, Rope, RopeElem};
+
+    /// A handle into a [RopeStore], identifying one interned [Rope].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub struct RopeHandle(u64);
+
+    /// A reference to one chunk in the dedup table: its content hash, plus
+    /// an index into that hash's bucket. A 64-bit hash isn't collision-free,
+    /// so more than one distinct chunk can land in the same bucket -- the
+    /// index disambiguates them instead of one silently overwriting (or
+    /// being treated as identical to) the other.
+    #[derive(Clone, Copy, Serialize, Deserialize)]
+    struct ChunkRef {
+        hash: u64,
+        index: usize,
+    }
+
+    /// On-disk representation of an interned [Rope]: an ordered list of
+    /// [ChunkRef]s, each of which must resolve against the store's chunk
+    /// table.
+    #[derive(Serialize, Deserialize)]
+    struct StoredRope {
+        chunks: Vec<ChunkRef>,
+    }
+
+    /// All distinct chunks whose content hashes to the same value. Almost
+    /// always has exactly one entry; a second only appears on a genuine hash
+    /// collision, verified by byte comparison on insert.
+    #[derive(Default, Serialize, Deserialize)]
+    struct ChunkBucket {
+        entries: Vec<Arc<Bytes>>,
+    }
+
+    /// Owns the chunk deduplication table used to persist a graph of [Rope]s
+    /// without re-serializing, or re-allocating on read, any chunk shared by
+    /// more than one of them.
+    #[derive(Default, Serialize, Deserialize)]
+    pub struct RopeStore {
+        chunks: HashMap<u64, ChunkBucket>,
+        ropes: HashMap<u64, StoredRope>,
+        next_handle: u64,
+    }
+
+    impl RopeStore {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Interns `rope`'s chunks into the dedup table -- a chunk whose
+        /// bytes already match an entry in its hash's bucket isn't written
+        /// again -- and returns a handle that can later be
+        /// [resolve](Self::resolve)d back into an equivalent `Rope`.
+        pub fn intern(&mut self, rope: &Rope) -> RopeHandle {
+            let mut elems = Vec::new();
+            rope.collect_leaves(&mut elems);
+
+            let mut chunks = Vec::with_capacity(elems.len());
+            for el in &elems {
+                let bytes = el.as_slice();
+                let hash = hash_xxh3_hash64(bytes);
+                let bucket = self.chunks.entry(hash).or_default();
+
+                let index = match bucket.entries.iter().position(|c| c.as_slice() == bytes) {
+                    Some(index) => index,
+                    None => {
+                        bucket.entries.push(match el {
+                            RopeElem::Shared(arc) => Arc::clone(arc),
+                            RopeElem::Inline { .. } => Arc::new(bytes.to_vec()),
+                        });
+                        bucket.entries.len() - 1
+                    }
+                };
+
+                chunks.push(ChunkRef { hash, index });
+            }
+
+            let handle = self.next_handle;
+            self.next_handle += 1;
+            self.ropes.insert(handle, StoredRope { chunks });
+            RopeHandle(handle)
+        }
+
+        /// Rebuilds the `Rope` referenced by `handle`. Chunks shared with
+        /// other interned ropes resolve to the same `Arc` allocation rather
+        /// than a fresh copy.
+        pub fn resolve(&self, handle: RopeHandle) -> Option<Rope> {
+            let stored = self.ropes.get(&handle.0)?;
+            let mut rope = Rope::default();
+            for chunk_ref in &stored.chunks {
+                let bucket = self.chunks.get(&chunk_ref.hash)?;
+                let bytes = bucket.entries.get(chunk_ref.index)?;
+                rope.push_shared_bytes(Arc::clone(bytes));
+            }
+            Some(rope)
+        }
+
+        /// Writes the store using the CBOR-based binary codec.
+        pub fn to_writer<W: std::io::Write>(
+            &self,
+            writer: W,
+        ) -> Result<(), ciborium::ser::Error<std::io::Error>> {
+            ciborium::into_writer(self, writer)
+        }
+
+        /// Reads back a store previously written by
+        /// [to_writer](Self::to_writer).
+        pub fn from_reader<R: std::io::Read>(
+            reader: R,
+        ) -> Result<Self, ciborium::de::Error<std::io::Error>> {
+            ciborium::from_reader(reader)
+        }
+    }
+}
+
+#[cfg(test)]
+mod rope_tests {
+    use super::*;
+
+    /// Builds a rope out of many small, separately-pushed chunks -- forcing
+    /// repeated `concat` calls and, with them, repeated rebalancing -- and
+    /// returns it alongside the flattened bytes it should contain.
+    fn build_rope(n_chunks: usize) -> (Rope, Vec<u8>) {
+        let mut rope = Rope::default();
+        let mut expected = Vec::new();
+        for i in 0..n_chunks {
+            let chunk = format!("chunk-{i}-");
+            expected.extend_from_slice(chunk.as_bytes());
+            rope.push_shared_bytes(Arc::new(chunk.into_bytes()));
+        }
+        (rope, expected)
+    }
+
+    #[test]
+    fn rebalance_keeps_depth_within_fib_limit() {
+        let (rope, expected) = build_rope(200);
+        assert_eq!(rope.len(), expected.len());
+        assert_eq!(rope.flatten().to_vec(), expected);
+        assert!(rope.depth() <= fib_depth_limit(rope.leaf_count()));
+    }
+
+    #[test]
+    fn slice_and_read_match_flattened_ranges() {
+        let (rope, expected) = build_rope(50);
+        let ranges = [
+            (0, 0),
+            (0, expected.len()),
+            (3, 17),
+            (expected.len() - 1, expected.len()),
+            (expected.len(), expected.len()),
+        ];
+        for (start, end) in ranges {
+            let mut buf = Vec::new();
+            rope.slice(start, end).read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, expected[start..end], "slice({start}, {end})");
+        }
+
+        let mut full = Vec::new();
+        rope.read().read_to_end(&mut full).unwrap();
+        assert_eq!(full, expected);
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+
+    /// Builds a rope out of separately-pushed chunks, so a line that spans
+    /// two of them straddles a leaf boundary instead of living in one leaf.
+    fn rope_from_chunks(chunks: &[&str]) -> Rope {
+        let mut rope = Rope::default();
+        for chunk in chunks {
+            rope.push_shared_bytes(Arc::new(chunk.as_bytes().to_vec()));
+        }
+        rope
+    }
+
+    /// Reconstructs the `other` rope a [RopeDiff] was computed against, by
+    /// keeping its `Equal` and `Insert` spans and dropping `Delete` spans.
+    fn apply_diff(diff: &RopeDiff) -> Rope {
+        let mut out = Rope::default();
+        for span in &diff.0 {
+            match span {
+                RopeDiffSpan::Equal(r) | RopeDiffSpan::Insert(r) => out.concat(r),
+                RopeDiffSpan::Delete(_) => {}
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn diff_round_trips_empty_ropes() {
+        let a = Rope::default();
+        let b = Rope::default();
+        let diff = a.diff(&b);
+        assert_eq!(apply_diff(&diff).to_string().unwrap(), "");
+    }
+
+    #[test]
+    fn diff_round_trips_trailing_newline_only() {
+        let a = Rope::new(b"hello\n".to_vec());
+        let b = Rope::new(b"hello".to_vec());
+        let diff = a.diff(&b);
+        assert_eq!(apply_diff(&diff).to_string().unwrap(), "hello");
+    }
+
+    #[test]
+    fn diff_round_trips_line_straddling_leaf_boundary() {
+        let a = rope_from_chunks(&["fo", "o\nbar\n"]);
+        let b = Rope::new(b"foo\nbaz\n".to_vec());
+        let diff = a.diff(&b);
+        assert_eq!(apply_diff(&diff).to_string().unwrap(), "foo\nbaz\n");
+    }
 }
\ No newline at end of file